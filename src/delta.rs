@@ -1,23 +1,54 @@
 use super::rolling_sum::RollingSum;
-use super::signature::{chunk_strong_hash, is_chunk_last, ChunkHash, FileSignature};
-use crate::file_ops::read_file_to_buffer;
-use bincode::{deserialize_from, serialize_into};
+use super::signature::{
+    chunk_strong_hash, read_signature, CdcChunker, CdcParams, ChunkHash, ChunkingMode,
+    FileSignature,
+};
+use crate::file_ops::ProgressReader;
+use crate::rolling_sum::chunk_rollsum;
+use bincode::serialize_into;
 use serde::{Deserialize, Serialize};
-use std::cmp;
 use std::cmp::PartialEq;
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Result};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::iter::Peekable;
 
-/// Enum type representing 2 types of operations
+/// Enum type representing the operations
 /// that can be applied to reconstruct modified file
 /// Match - the whole chunk matches, it holds chunk index
 /// NoMatch - weak signature doesn't match, it holds vector of non-matching bytes
+/// MatchRun - a run of `count` contiguous matched chunks starting at `start`
+///
+/// `MatchRun` is appended last on purpose: bincode variant discriminants are
+/// positional, so keeping `Match`/`NoMatch` at indices 0/1 lets deltas written
+/// before run-length encoding still decode.
 ///
 /// Vector of `Operation`s is serialized to delta file
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Operation {
     Match(u32),
     NoMatch(Vec<u8>),
+    MatchRun { start: u32, count: u32 },
+}
+
+/// Magic/version header prepended to every delta file so that `apply_delta`
+/// can reject truncated or foreign files and so the format can evolve.
+pub const DELTA_MAGIC: [u8; 4] = *b"RDF1";
+
+/// The delta file format addresses matched chunks by `index * chunk_size`,
+/// which only holds for fixed-size blocks. Content-defined chunks are
+/// variable-length, so `apply_delta` could not recover their basis offsets and
+/// would silently reconstruct garbage. Reject such signatures up front; the
+/// in-memory `generate_delta` still serves CDC callers that reconstruct with
+/// access to the signature.
+fn reject_content_defined(signature: &FileSignature) -> Result<()> {
+    if let ChunkingMode::ContentDefined(_) = signature.mode {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "content-defined signatures are not supported by the delta file format",
+        ));
+    }
+    Ok(())
 }
 
 /// Creates delta file given signature file and modified file
@@ -25,89 +56,222 @@ pub fn create_delta_file(
     sig_file: &File,
     modified_file: &File,
     delta_file: &mut File,
+    progress: Option<&mut dyn FnMut(f32)>,
 ) -> Result<()> {
-    let sig_reader = BufReader::new(sig_file);
-    let signature: FileSignature = deserialize_from(sig_reader).unwrap();
+    let signature = read_signature(BufReader::new(sig_file))?;
     let chunk_size = signature.chunk_size as usize;
-    let mut mod_file_reader = BufReader::new(modified_file);
-    let mut buffer = read_file_to_buffer(&mut mod_file_reader)?;
+    reject_content_defined(&signature)?;
 
-    let delta = generate_delta(&mut buffer, &signature, chunk_size);
+    let delta = match progress {
+        Some(callback) => {
+            let total_len = modified_file.metadata().map_or(0, |meta| meta.len());
+            let reader = ProgressReader::new(modified_file, total_len, callback);
+            generate_delta(reader, &signature, chunk_size)
+        }
+        None => generate_delta(modified_file, &signature, chunk_size),
+    };
 
+    // Write the magic header, then embed the chunk size ahead of the operations
+    // so that `apply_delta` can rebuild the target without also being handed the
+    // signature file.
     let mut delta_writer = BufWriter::new(delta_file);
+    delta_writer.write_all(&DELTA_MAGIC)?;
+    serialize_into(&mut delta_writer, &(chunk_size as u32)).unwrap();
     serialize_into(&mut delta_writer, &delta).unwrap();
 
     Ok(())
 }
 
-/// Generates delta for given buffer, signature and chunk size
+/// Produces a delta from a signature stream and a target stream, writing the
+/// delta (magic header, chunk size, operations) to `out`. Neither the basis
+/// file nor the target file needs to live on the same machine: one side ships
+/// the signature bytes, the other streams its target and emits the delta.
+pub fn generate_delta_from<R: Read, W: Write>(sig: impl Read, target: R, out: W) -> Result<()> {
+    let signature = read_signature(BufReader::new(sig))?;
+    let chunk_size = signature.chunk_size as usize;
+    reject_content_defined(&signature)?;
+
+    let delta = generate_delta(target, &signature, chunk_size);
+
+    let mut writer = BufWriter::new(out);
+    writer.write_all(&DELTA_MAGIC)?;
+    serialize_into(&mut writer, &(chunk_size as u32)).unwrap();
+    serialize_into(&mut writer, &delta).unwrap();
+    Ok(())
+}
+
+/// Generates delta for given modified file reader, signature and chunk size
 ///
-/// Buffer is consumed
-pub fn generate_delta(
-    buffer: &mut Vec<u8>,
+/// The modified file is streamed through a sliding window of at most
+/// `chunk_size` bytes (a `VecDeque` refilled from the reader as bytes are
+/// consumed from the front) instead of being loaded into one buffer up front.
+/// Each literal run is collected whole and pushed as a single
+/// `Operation::NoMatch` once a match or the end of input ends it; the returned
+/// vector holds every literal byte, so memory use grows with unmatched data.
+pub fn generate_delta<R: Read>(
+    reader: R,
+    sig: &FileSignature,
+    chunk_size: usize,
+) -> Vec<Operation> {
+    let operations = match sig.mode {
+        ChunkingMode::Fixed => generate_delta_fixed(reader, sig, chunk_size),
+        ChunkingMode::ContentDefined(params) => generate_delta_cdc(reader, sig, params),
+    };
+    coalesce_matches(operations)
+}
+
+/// Collapses runs of adjacent `Match(start)`, `Match(start + 1)`, … into a single
+/// `Operation::MatchRun { start, count }`, so a large unchanged region costs one
+/// command instead of 4+ bytes per chunk. A lone match stays a plain `Match`.
+fn coalesce_matches(operations: Vec<Operation>) -> Vec<Operation> {
+    let mut result: Vec<Operation> = Vec::with_capacity(operations.len());
+    // (start, count) of the run currently being accumulated
+    let mut run: Option<(u32, u32)> = None;
+
+    for op in operations {
+        match op {
+            Operation::Match(idx) => match run {
+                Some((start, count)) if start + count == idx => {
+                    run = Some((start, count + 1));
+                }
+                _ => {
+                    flush_run(&mut result, run.take());
+                    run = Some((idx, 1));
+                }
+            },
+            other => {
+                flush_run(&mut result, run.take());
+                result.push(other);
+            }
+        }
+    }
+    flush_run(&mut result, run.take());
+    result
+}
+
+fn flush_run(result: &mut Vec<Operation>, run: Option<(u32, u32)>) {
+    if let Some((start, count)) = run {
+        if count == 1 {
+            result.push(Operation::Match(start));
+        } else {
+            result.push(Operation::MatchRun { start, count });
+        }
+    }
+}
+
+/// Fixed-block delta: slides a `chunk_size` window over the target byte by byte
+/// using the rsync rolling checksum, emitting each literal run when a match or
+/// the end of input ends it.
+fn generate_delta_fixed<R: Read>(
+    reader: R,
     sig: &FileSignature,
     chunk_size: usize,
 ) -> Vec<Operation> {
     let mut operations: Vec<Operation> = Vec::new();
+    let mut bytes = BufReader::new(reader).bytes().peekable();
+
+    // The sliding window holds the current chunk candidate, at most
+    // `chunk_size` bytes. Near the end of file it shrinks below that.
+    let mut window: VecDeque<u8> = VecDeque::with_capacity(chunk_size);
+    fill_window(&mut window, &mut bytes, chunk_size);
+
     loop {
-        // In case less than whole chunk is left,
-        // we have to narrow down the buffer to the leftover
-        let chunk = &buffer[..cmp::min(chunk_size, buffer.len())];
-        let mut chunk_len = chunk.len();
-        if chunk_len == 0 {
+        if window.is_empty() {
             break;
         }
 
-        // Calculate weak signature (using rsync rolling checksum algorithm) for chunk
+        // Calculate weak signature (using rsync rolling checksum algorithm) for window
         let mut rolling_sum = RollingSum::new();
-        rolling_sum.update(chunk);
+        rolling_sum.update(window.make_contiguous());
         let weak_hash = rolling_sum.digest();
 
-        if let Some(hash) = chunk_hash_matching_weak_n_strong(sig, weak_hash, chunk) {
+        if let Some(hash) = chunk_hash_matching_weak_n_strong(sig, weak_hash, window.make_contiguous())
+        {
             operations.push(Operation::Match(hash.chunk_index));
 
-            if is_chunk_last(chunk_len, buffer.len()) {
+            // The reader being exhausted means this window was the last chunk.
+            if bytes.peek().is_none() {
                 break;
             }
-            // Prepare buffer for next iteration
-            buffer.drain(..chunk_len);
+            // Prepare window for next iteration
+            window.clear();
+            fill_window(&mut window, &mut bytes, chunk_size);
             continue;
         }
 
         let mut not_matching_bytes: Vec<u8> = Vec::new();
         loop {
-            let mut buf_len = buffer.len();
-            let mut next: Option<u8> = None;
-            if !is_chunk_last(chunk_len, buf_len) {
-                next = Some(buffer[chunk_size]);
-            }
-            if buf_len > 0 {
-                let prev = buffer.remove(0);
-                buf_len = buffer.len();
-                not_matching_bytes.push(prev);
-                rolling_sum.roll_fw(prev, next);
-                let weak_hash = rolling_sum.digest();
-                let chunk = &buffer[..cmp::min(chunk_size, buf_len)];
-                chunk_len = chunk.len();
-
-                if let Some(hash) = chunk_hash_matching_weak_n_strong(sig, weak_hash, chunk) {
-                    operations.push(Operation::NoMatch(not_matching_bytes));
-                    operations.push(Operation::Match(hash.chunk_index));
-                    // Prepare buffer for next iteration
-                    buffer.drain(..chunk_len);
-                    break;
-                }
-            } else {
+            if window.is_empty() {
                 if !not_matching_bytes.is_empty() {
                     operations.push(Operation::NoMatch(not_matching_bytes));
                 }
                 break;
             }
+
+            // The byte right after the window rolls into it as the front byte
+            // leaves. When the reader is exhausted the window simply shrinks.
+            let next = match bytes.next() {
+                Some(Ok(byte)) => Some(byte),
+                _ => None,
+            };
+            let prev = window.pop_front().unwrap();
+            if let Some(byte) = next {
+                window.push_back(byte);
+            }
+            not_matching_bytes.push(prev);
+            rolling_sum.roll_fw(prev, next);
+            let weak_hash = rolling_sum.digest();
+
+            if let Some(hash) =
+                chunk_hash_matching_weak_n_strong(sig, weak_hash, window.make_contiguous())
+            {
+                operations.push(Operation::NoMatch(not_matching_bytes));
+                operations.push(Operation::Match(hash.chunk_index));
+                // Prepare window for next iteration
+                window.clear();
+                fill_window(&mut window, &mut bytes, chunk_size);
+                break;
+            }
         }
     }
     operations
 }
 
+/// Content-defined delta: re-cuts the target with the same FastCDC parameters
+/// recorded in the signature and emits one operation per chunk. Because both
+/// sides cut at data-dependent boundaries there is no need to roll byte by byte.
+fn generate_delta_cdc<R: Read>(
+    reader: R,
+    sig: &FileSignature,
+    params: CdcParams,
+) -> Vec<Operation> {
+    let mut operations: Vec<Operation> = Vec::new();
+    for chunk in CdcChunker::new(reader, params) {
+        let weak_hash = chunk_rollsum(&chunk);
+        if let Some(hash) = chunk_hash_matching_weak_n_strong(sig, weak_hash, &chunk) {
+            operations.push(Operation::Match(hash.chunk_index));
+        } else {
+            operations.push(Operation::NoMatch(chunk));
+        }
+    }
+    operations
+}
+
+/// Refill the sliding window from the reader up to `chunk_size` bytes,
+/// pulling the next byte only when the window needs it.
+fn fill_window<I: Iterator<Item = Result<u8>>>(
+    window: &mut VecDeque<u8>,
+    bytes: &mut Peekable<I>,
+    chunk_size: usize,
+) {
+    while window.len() < chunk_size {
+        match bytes.next() {
+            Some(Ok(byte)) => window.push_back(byte),
+            _ => break,
+        }
+    }
+}
+
 fn chunk_hash_matching_weak_n_strong<'a>(
     sig: &'a FileSignature,
     weak_hash: u32,
@@ -125,23 +289,22 @@ fn chunk_hash_matching_weak_n_strong<'a>(
 mod test {
     use super::*;
     use crate::file_ops::open_read_handler;
+    use crate::signature::generate_signature;
+    use bincode::deserialize_from;
     use std::path::Path;
 
     #[test]
     pub fn test_generate_delta() {
         let sig_path = Path::new("test/signature");
         let sig_file = open_read_handler(sig_path).unwrap();
-        let sig_reader = BufReader::new(sig_file);
 
         let new_file_path = Path::new("test/new");
         let new_file = open_read_handler(new_file_path).unwrap();
-        let mut new_file_reader = BufReader::new(&new_file);
 
-        let mut buffer = read_file_to_buffer(&mut new_file_reader).unwrap();
-        let signature: FileSignature = deserialize_from(sig_reader).unwrap();
+        let signature = read_signature(BufReader::new(sig_file)).unwrap();
         let chunk_size = signature.chunk_size;
 
-        let delta = generate_delta(&mut buffer, &signature, chunk_size as usize);
+        let delta = generate_delta(&new_file, &signature, chunk_size as usize);
 
         let expected_delta_path = Path::new("test/delta");
         let expected_delta_file = open_read_handler(expected_delta_path).unwrap();
@@ -151,4 +314,82 @@ mod test {
 
         assert_eq!(delta, expected_delta);
     }
+
+    #[test]
+    pub fn test_coalesce_matches() {
+        let ops = vec![
+            Operation::Match(0),
+            Operation::Match(1),
+            Operation::Match(2),
+            Operation::NoMatch(vec![9, 9]),
+            Operation::Match(5),
+            Operation::Match(6),
+            Operation::Match(8),
+        ];
+
+        let coalesced = coalesce_matches(ops);
+
+        assert_eq!(
+            coalesced,
+            vec![
+                Operation::MatchRun { start: 0, count: 3 },
+                Operation::NoMatch(vec![9, 9]),
+                Operation::MatchRun { start: 5, count: 2 },
+                Operation::Match(8),
+            ]
+        );
+    }
+
+    /// Rebuild the target from a fixed-block delta the same way `apply_delta`
+    /// does, so the round-trip can be checked without temporary files.
+    fn reconstruct(basis: &[u8], chunk_size: usize, ops: &[Operation]) -> Vec<u8> {
+        fn copy(out: &mut Vec<u8>, basis: &[u8], chunk_size: usize, index: u32) {
+            let start = index as usize * chunk_size;
+            let end = std::cmp::min(start + chunk_size, basis.len());
+            out.extend_from_slice(&basis[start..end]);
+        }
+
+        let mut out = Vec::new();
+        for op in ops {
+            match op {
+                Operation::Match(index) => copy(&mut out, basis, chunk_size, *index),
+                Operation::MatchRun { start, count } => {
+                    for index in *start..*start + *count {
+                        copy(&mut out, basis, chunk_size, index);
+                    }
+                }
+                Operation::NoMatch(bytes) => out.extend_from_slice(bytes),
+            }
+        }
+        out
+    }
+
+    #[test]
+    pub fn test_delta_round_trip_identical() {
+        let chunk_size = 700usize;
+        let basis: Vec<u8> = (0..3500u32).map(|i| (i % 251) as u8).collect();
+
+        let signature = generate_signature(&mut basis.clone(), chunk_size as u32);
+        let delta = generate_delta(basis.as_slice(), &signature, chunk_size);
+
+        // All five contiguous chunks match, so they collapse into one run.
+        assert_eq!(delta, vec![Operation::MatchRun { start: 0, count: 5 }]);
+        assert_eq!(reconstruct(&basis, chunk_size, &delta), basis);
+    }
+
+    #[test]
+    pub fn test_delta_round_trip_with_literal_tail() {
+        let chunk_size = 700usize;
+        let basis: Vec<u8> = (0..3500u32).map(|i| (i % 251) as u8).collect();
+        let tail = vec![200u8; chunk_size];
+        let mut modified = basis.clone();
+        modified.extend_from_slice(&tail);
+
+        let signature = generate_signature(&mut basis.clone(), chunk_size as u32);
+        let delta = generate_delta(modified.as_slice(), &signature, chunk_size);
+
+        assert_eq!(delta[0], Operation::MatchRun { start: 0, count: 5 });
+        assert_eq!(delta.last(), Some(&Operation::NoMatch(tail)));
+        assert_eq!(reconstruct(&basis, chunk_size, &delta), modified);
+    }
 }
@@ -1,8 +1,62 @@
+use std::cmp;
 use std::fs::File;
 use std::io::Result;
 use std::io::{BufReader, Read};
 use std::path::Path;
 
+/// Wraps a reader and reports read progress as a fraction in `[0.0, 1.0]`.
+///
+/// The callback fires roughly once per percent of `total_len` (the step is
+/// precomputed so there is no division on the hot path) so embedders can drive
+/// a progress bar over long signature/delta passes without touching the core
+/// algorithms.
+pub struct ProgressReader<R: Read, F: FnMut(f32)> {
+    inner: R,
+    total_len: u64,
+    read_so_far: u64,
+    step: u64,
+    since_last: u64,
+    callback: F,
+}
+
+impl<R: Read, F: FnMut(f32)> ProgressReader<R, F> {
+    pub fn new(inner: R, total_len: u64, callback: F) -> Self {
+        Self {
+            inner,
+            total_len,
+            read_so_far: 0,
+            // Report about once per percent, but never less than every byte.
+            step: cmp::max(1, total_len / 100),
+            since_last: 0,
+            callback,
+        }
+    }
+
+    fn fraction(&self) -> f32 {
+        if self.total_len == 0 {
+            1.0
+        } else {
+            self.read_so_far as f32 / self.total_len as f32
+        }
+    }
+}
+
+impl<R: Read, F: FnMut(f32)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read_so_far += n as u64;
+        self.since_last += n as u64;
+        if self.since_last >= self.step {
+            self.since_last = 0;
+            // Hoist the fraction: invoking `callback` borrows the field mutably,
+            // so the argument cannot also borrow `*self`.
+            let fraction = self.fraction();
+            (self.callback)(fraction);
+        }
+        Ok(n)
+    }
+}
+
 pub fn read_file_to_buffer(reader: &mut BufReader<&File>) -> Result<Vec<u8>> {
     let mut buffer: Vec<u8> = Vec::new();
     reader.read_to_end(&mut buffer)?;
@@ -1,10 +1,14 @@
 pub mod delta;
 pub mod file_ops;
+pub mod patch;
 pub mod rolling_sum;
 pub mod signature;
 
-pub use delta::{create_delta_file, generate_delta, Operation};
+pub use delta::{create_delta_file, generate_delta, generate_delta_from, Operation, DELTA_MAGIC};
+pub use patch::apply_delta;
 pub use rolling_sum::{chunk_rollsum, RollingSum};
 pub use signature::{
-    chunk_strong_hash, create_signature_file, generate_signature, ChunkHash, FileSignature,
+    chunk_strong_hash, create_signature_file, generate_signature, generate_signature_cdc,
+    generate_signature_to, read_signature, CdcChunker, CdcParams, ChunkHash, ChunkingMode,
+    FileSignature, SIGNATURE_MAGIC,
 };
@@ -2,11 +2,13 @@ use clap::Parser;
 use delta::create_delta_file;
 use file_ops::{open_read_handler, open_write_handler};
 use opts::*;
+use patch::apply_delta;
 use signature::create_signature_file;
 
 mod delta;
 mod file_ops;
 mod opts;
+mod patch;
 mod rolling_sum;
 mod signature;
 
@@ -19,7 +21,13 @@ fn main() {
             println!("signature file: {}", s.signature_file.display());
             let unchanged_file = open_read_handler(&s.unchanged_file).unwrap();
             let mut signature_file = open_write_handler(&s.signature_file).unwrap();
-            create_signature_file(&unchanged_file, &mut signature_file).unwrap();
+            let mut report = |pct: f32| println!("progress: {:.0}%", pct * 100.0);
+            let progress = if s.progress {
+                Some(&mut report as &mut dyn FnMut(f32))
+            } else {
+                None
+            };
+            create_signature_file(&unchanged_file, &mut signature_file, progress).unwrap();
         }
         SubCommand::Delta(d) => {
             println!("signature file: {}", d.signature_file.display());
@@ -28,7 +36,22 @@ fn main() {
             let signature_file = open_read_handler(&d.signature_file).unwrap();
             let modified_file = open_read_handler(&d.modified_file).unwrap();
             let mut delta_file = open_write_handler(&d.delta_file).unwrap();
-            create_delta_file(&signature_file, &modified_file, &mut delta_file).unwrap();
+            let mut report = |pct: f32| println!("progress: {:.0}%", pct * 100.0);
+            let progress = if d.progress {
+                Some(&mut report as &mut dyn FnMut(f32))
+            } else {
+                None
+            };
+            create_delta_file(&signature_file, &modified_file, &mut delta_file, progress).unwrap();
+        }
+        SubCommand::Patch(p) => {
+            println!("basis file: {}", p.basis_file.display());
+            println!("delta file: {}", p.delta_file.display());
+            println!("output file: {}", p.output_file.display());
+            let basis_file = open_read_handler(&p.basis_file).unwrap();
+            let delta_file = open_read_handler(&p.delta_file).unwrap();
+            let mut output_file = open_write_handler(&p.output_file).unwrap();
+            apply_delta(&basis_file, &delta_file, &mut output_file).unwrap();
         }
     }
 }
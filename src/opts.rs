@@ -19,6 +19,7 @@ pub enum SubCommand {
     #[clap(version = "0.1.0", author = "Tomasz Augustyn <t.augustyn@poczta.fm>")]
     Signature(Signature),
     Delta(Delta),
+    Patch(Patch),
 }
 
 /// A subcommand for generating signature file for file before changes
@@ -30,6 +31,9 @@ pub struct Signature {
     /// Signature file
     #[clap(name = "SIGNATURE_FILE", parse(from_os_str))]
     pub signature_file: PathBuf,
+    /// Print progress percentage while reading the input file
+    #[clap(long)]
+    pub progress: bool,
 }
 
 /// A subcommand for creating delta using signature file and modified file
@@ -44,4 +48,21 @@ pub struct Delta {
     /// Delta file
     #[clap(name = "DELTA_FILE", parse(from_os_str))]
     pub delta_file: PathBuf,
+    /// Print progress percentage while reading the modified file
+    #[clap(long)]
+    pub progress: bool,
+}
+
+/// A subcommand for reconstructing the modified file using basis file and delta file
+#[derive(Parser)]
+pub struct Patch {
+    /// File before changes
+    #[clap(name = "BASIS_FILE", parse(from_os_str))]
+    pub basis_file: PathBuf,
+    /// Delta file
+    #[clap(name = "DELTA_FILE", parse(from_os_str))]
+    pub delta_file: PathBuf,
+    /// Reconstructed output file
+    #[clap(name = "OUTPUT_FILE", parse(from_os_str))]
+    pub output_file: PathBuf,
 }
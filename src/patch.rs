@@ -0,0 +1,73 @@
+use super::delta::{Operation, DELTA_MAGIC};
+use bincode::deserialize_from;
+use std::cmp;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+/// Reconstructs the modified file from the basis file plus a delta.
+///
+/// The delta starts with the [`DELTA_MAGIC`] header, followed by the
+/// `chunk_size` it was produced with (see `create_delta_file`) and the
+/// serialized `Vec<Operation>`. For each matched chunk one chunk is copied from
+/// the basis file starting at `index * chunk_size`, for each
+/// `Operation::NoMatch(bytes)` the literal bytes are written straight to the
+/// output.
+pub fn apply_delta(basis: &File, delta: &File, out: &mut File) -> Result<()> {
+    let mut delta_reader = BufReader::new(delta);
+
+    // Reject truncated or foreign delta files before interpreting their bytes.
+    let mut magic = [0u8; DELTA_MAGIC.len()];
+    delta_reader.read_exact(&mut magic)?;
+    if magic != DELTA_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "unrecognized delta file: bad magic header",
+        ));
+    }
+
+    let chunk_size: u32 = deserialize_from(&mut delta_reader)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "truncated or corrupt delta file"))?;
+    let chunk_size = chunk_size as u64;
+    let operations: Vec<Operation> = deserialize_from(&mut delta_reader)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "truncated or corrupt delta file"))?;
+
+    let basis_len = basis.metadata()?.len();
+    let mut basis_reader = BufReader::new(basis);
+    let mut out_writer = BufWriter::new(out);
+
+    for operation in &operations {
+        match operation {
+            Operation::Match(chunk_index) => {
+                copy_chunk(&mut basis_reader, &mut out_writer, *chunk_index, chunk_size, basis_len)?;
+            }
+            Operation::MatchRun { start, count } => {
+                for chunk_index in *start..*start + *count {
+                    copy_chunk(&mut basis_reader, &mut out_writer, chunk_index, chunk_size, basis_len)?;
+                }
+            }
+            Operation::NoMatch(bytes) => {
+                out_writer.write_all(bytes)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copies a single matched chunk from the basis file to the output, clamping the
+/// copy length to the basis file length since the last chunk may be shorter than
+/// `chunk_size`.
+fn copy_chunk<R: Read + Seek, W: Write>(
+    basis_reader: &mut R,
+    out_writer: &mut W,
+    chunk_index: u32,
+    chunk_size: u64,
+    basis_len: u64,
+) -> Result<()> {
+    let offset = (chunk_index as u64) * chunk_size;
+    let copy_len = cmp::min(chunk_size, basis_len.saturating_sub(offset));
+    basis_reader.seek(SeekFrom::Start(offset))?;
+    let mut chunk = vec![0u8; copy_len as usize];
+    basis_reader.read_exact(&mut chunk)?;
+    out_writer.write_all(&chunk)?;
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use super::rolling_sum::chunk_rollsum;
-use bincode::serialize_into;
+use crate::file_ops::ProgressReader;
+use bincode::{deserialize_from, serialize_into};
 use blake2::digest::{Update, VariableOutput};
 use blake2::VarBlake2b;
 use serde::{Deserialize, Serialize};
@@ -7,18 +8,92 @@ use std::cmp;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Result};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
 
 /// Default block size in rsync C implementation
 const BLOCK_SIZE: u32 = 700;
 /// Hasher output length for strong signature (Blake2) need to be 32 bytes (256 bits)
 /// to comply with rsync C implementation
 const RS_MAX_STRONG_SUM_LENGTH: usize = 32;
+/// Number of entries in the gear table used by content-defined chunking
+const GEAR_SIZE: usize = 256;
+
+/// Magic/version header prepended to every signature file. It lets readers
+/// reject foreign files and distinguish format versions with a clear error
+/// instead of a bincode panic. Note this is an on-disk format bump: adding
+/// `mode`/`chunk_len` changed the positional bincode layout, so signatures
+/// written by earlier versions of the crate no longer decode — the magic is how
+/// that mismatch surfaces cleanly rather than as garbage or a panic.
+pub const SIGNATURE_MAGIC: [u8; 4] = *b"RSG1";
+
+/// Fixed pseudo-random gear table for the FastCDC rolling fingerprint.
+/// It is generated deterministically (splitmix64 seeded with a constant) so
+/// that the signature and delta sides always agree on the cut points.
+const GEAR: [u64; GEAR_SIZE] = build_gear_table();
+
+const fn build_gear_table() -> [u64; GEAR_SIZE] {
+    let mut table = [0u64; GEAR_SIZE];
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut i = 0;
+    while i < GEAR_SIZE {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        table[i] = z;
+        i += 1;
+    }
+    table
+}
+
+/// How the basis file was cut into chunks. Fixed-size blocks use the `Fixed`
+/// variant; `ContentDefined` carries the FastCDC parameters so `generate_delta`
+/// can re-derive the very same boundaries. Signature files written before this
+/// field existed do not decode (see `SIGNATURE_MAGIC`).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ChunkingMode {
+    Fixed,
+    ContentDefined(CdcParams),
+}
+
+/// FastCDC parameters. `mask_s` is stricter (more 1-bits) and is used while the
+/// current chunk is still below the target average size; `mask_l` is looser and
+/// takes over once past it ("normalized chunking"). `min_size`/`max_size` clamp
+/// the chunk length so chunks never get pathologically small or huge.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct CdcParams {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+    pub mask_s: u64,
+    pub mask_l: u64,
+}
+
+impl CdcParams {
+    /// Derives sensible bounds and masks from a target average chunk size,
+    /// following the FastCDC paper: `min = avg / 4`, `max = avg * 4`, and masks
+    /// with `log2(avg) ± 2` one-bits for the strict and loose regions.
+    pub fn new(avg_size: u32) -> Self {
+        let bits = (u32::BITS - avg_size.leading_zeros()).saturating_sub(1);
+        let mask = |b: u32| -> u64 { (1u64 << b) - 1 };
+        Self {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size * 4,
+            mask_s: mask(bits + 2),
+            mask_l: mask(bits.saturating_sub(2)),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileSignature {
-    /// Chunk size used to calculate weak and strong signatures
+    /// Chunk size used to calculate weak and strong signatures. For content-defined
+    /// chunking this holds the target average size.
     pub chunk_size: u32,
+    /// How the basis file was chunked
+    pub mode: ChunkingMode,
     /// Key is a weak signature (rsync rolling checksum algorithm)
     /// Value is a vector of all strong hashes together with the index
     /// of their chunk for which weak signature is the same
@@ -28,6 +103,9 @@ pub struct FileSignature {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChunkHash {
     pub chunk_index: u32,
+    /// Actual length of the chunk in bytes. Constant for fixed blocks (except the
+    /// last one) but variable under content-defined chunking.
+    pub chunk_len: u32,
     pub strong_hash: [u8; RS_MAX_STRONG_SUM_LENGTH],
 }
 
@@ -37,26 +115,76 @@ impl FileSignature {
     }
 }
 
-pub fn create_signature_file(input_file: &File, sig_file: &mut File) -> Result<()> {
+pub fn create_signature_file(
+    input_file: &File,
+    sig_file: &mut File,
+    progress: Option<&mut dyn FnMut(f32)>,
+) -> Result<()> {
     // Fallback set to BLOCK_SIZE
     let chunk_size = input_file
         .metadata()
         .map_or(BLOCK_SIZE, |meta| calculate_chunk_size(meta.len()));
 
-    let mut input_reader = BufReader::new(input_file);
-    let mut buffer = super::read_file_to_buffer(&mut input_reader)?;
+    let total_len = input_file.metadata().map_or(0, |meta| meta.len());
+    let input_reader = BufReader::new(input_file);
+
+    let mut buffer: Vec<u8> = Vec::new();
+    match progress {
+        Some(callback) => {
+            ProgressReader::new(input_reader, total_len, callback).read_to_end(&mut buffer)?;
+        }
+        None => {
+            let mut input_reader = input_reader;
+            input_reader.read_to_end(&mut buffer)?;
+        }
+    }
 
     let signature = generate_signature(&mut buffer, chunk_size);
 
-    // Write serialized signature to file
+    // Write the magic header, then the serialized signature to file
     let mut sig_writer = BufWriter::new(sig_file);
+    sig_writer.write_all(&SIGNATURE_MAGIC)?;
     serialize_into(&mut sig_writer, &signature).unwrap();
     Ok(())
 }
 
+/// Reads and validates a signature produced by this crate, rejecting foreign or
+/// pre-format-change files (and truncated ones) with an `InvalidData` error
+/// instead of panicking inside bincode.
+pub fn read_signature<R: Read>(mut reader: R) -> Result<FileSignature> {
+    let mut magic = [0u8; SIGNATURE_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != SIGNATURE_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "unrecognized signature file: bad magic header",
+        ));
+    }
+    deserialize_from(reader)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "truncated or corrupt signature file"))
+}
+
+/// Streams `input` and writes its serialized signature to `out`, so the basis
+/// side of a remote diff can ship the signature bytes without the target side
+/// ever touching the file. The chunk size is derived from the amount of data
+/// read, mirroring `create_signature_file`.
+pub fn generate_signature_to<R: Read, W: Write>(mut input: R, out: W) -> Result<()> {
+    let mut buffer: Vec<u8> = Vec::new();
+    input.read_to_end(&mut buffer)?;
+
+    let chunk_size = calculate_chunk_size(buffer.len() as u64);
+    let signature = generate_signature(&mut buffer, chunk_size);
+
+    let mut writer = BufWriter::new(out);
+    writer.write_all(&SIGNATURE_MAGIC)?;
+    serialize_into(&mut writer, &signature).unwrap();
+    Ok(())
+}
+
 pub fn generate_signature(buffer: &mut Vec<u8>, chunk_size: u32) -> FileSignature {
     let mut signature = FileSignature {
         chunk_size,
+        mode: ChunkingMode::Fixed,
         signature_table: HashMap::new(),
     };
 
@@ -86,6 +214,7 @@ pub fn generate_signature(buffer: &mut Vec<u8>, chunk_size: u32) -> FileSignatur
 
         chunk_hashes.push(ChunkHash {
             chunk_index,
+            chunk_len: chunk_len as u32,
             strong_hash,
         });
 
@@ -100,6 +229,126 @@ pub fn generate_signature(buffer: &mut Vec<u8>, chunk_size: u32) -> FileSignatur
     signature
 }
 
+/// Builds a content-defined (FastCDC) signature by streaming the basis file and
+/// cutting it at data-dependent boundaries, so a byte inserted near the start
+/// only disturbs the chunks around the edit instead of shifting every boundary.
+///
+/// This is a **library-only** API: there is deliberately no CLI flag for it, and
+/// the delta file format cannot carry CDC deltas (`create_delta_file` /
+/// `generate_delta_from` reject CDC signatures, since `apply_delta` addresses
+/// chunks by `index * chunk_size`, which is meaningless for variable-length
+/// chunks). Embedders consume it in-memory via `generate_delta`, reconstructing
+/// from the operations while holding the signature themselves.
+pub fn generate_signature_cdc<R: Read>(reader: R, params: CdcParams) -> FileSignature {
+    let mut signature = FileSignature {
+        chunk_size: params.avg_size,
+        mode: ChunkingMode::ContentDefined(params),
+        signature_table: HashMap::new(),
+    };
+
+    let mut chunk_index = 0u32;
+    for chunk in CdcChunker::new(reader, params) {
+        let weak_hash = chunk_rollsum(&chunk);
+        let strong_hash = chunk_strong_hash(&chunk);
+
+        signature
+            .signature_table
+            .entry(weak_hash)
+            .or_insert_with(Vec::new)
+            .push(ChunkHash {
+                chunk_index,
+                chunk_len: chunk.len() as u32,
+                strong_hash,
+            });
+        chunk_index += 1;
+    }
+    signature
+}
+
+/// Streams a reader and yields content-defined chunks one at a time, buffering
+/// at most `max_size` bytes plus one 4 KiB read.
+pub struct CdcChunker<R: Read> {
+    reader: R,
+    params: CdcParams,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> CdcChunker<R> {
+    pub fn new(reader: R, params: CdcParams) -> Self {
+        Self {
+            reader,
+            params,
+            buffer: Vec::with_capacity(params.max_size as usize),
+            eof: false,
+        }
+    }
+
+    /// Pull bytes from the reader until the buffer holds at least `max_size`
+    /// bytes or the reader is exhausted.
+    fn fill(&mut self) {
+        let max = self.params.max_size as usize;
+        let mut tmp = [0u8; 4096];
+        while !self.eof && self.buffer.len() < max {
+            match self.reader.read(&mut tmp) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buffer.extend_from_slice(&tmp[..n]),
+                Err(_) => self.eof = true,
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for CdcChunker<R> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.fill();
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let cut = cdc_cut_point(&self.buffer, &self.params);
+        Some(self.buffer.drain(..cut).collect())
+    }
+}
+
+/// Finds the next FastCDC cut point within `buf`, using the stricter `mask_s`
+/// while below the average size and the looser `mask_l` past it, clamped to the
+/// `min_size`/`max_size` bounds.
+pub fn cdc_cut_point(buf: &[u8], params: &CdcParams) -> usize {
+    let len = buf.len();
+    let min = params.min_size as usize;
+    if len <= min {
+        return len;
+    }
+
+    let mut fp: u64 = 0;
+    let mut i = min;
+
+    // Skip the first `min_size` bytes, then hash with the strict mask up to the
+    // target average size.
+    let normal = cmp::min(params.avg_size as usize, len);
+    while i < normal {
+        fp = (fp << 1).wrapping_add(GEAR[buf[i] as usize]);
+        if fp & params.mask_s == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+
+    // Past the average size, relax to the loose mask until `max_size`.
+    let limit = cmp::min(params.max_size as usize, len);
+    while i < limit {
+        fp = (fp << 1).wrapping_add(GEAR[buf[i] as usize]);
+        if fp & params.mask_l == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+
+    limit
+}
+
 pub fn chunk_strong_hash(chunk: &[u8]) -> [u8; RS_MAX_STRONG_SUM_LENGTH] {
     // Use blake2 as MD5 is cryptographically broken:
     // https://www.kb.cert.org/vuls/id/836068